@@ -29,12 +29,160 @@ use crate::client;
 use crate::hal;
 use crate::hub;
 use crate::runtime_config;
+use crate::runtime_config::CoreAffinity;
 use crate::stats;
 
-use ii_async_compat::tokio;
+use futures::channel::{mpsc, oneshot};
+use ii_async_compat::{futures, tokio};
+use ii_logging::macros::*;
 
+use futures::StreamExt;
 use std::sync::Arc;
 
+/// Maps worker `index` (backend = 0, stats = 1, ...) to a core ID per `affinity`.
+fn resolve_core_id(affinity: &CoreAffinity, index: usize) -> Option<core_affinity::CoreId> {
+    let available = core_affinity::get_core_ids().unwrap_or_default();
+    if available.is_empty() {
+        warn!("core_affinity: no core IDs reported by the OS, pinning disabled");
+        return None;
+    }
+
+    match affinity {
+        CoreAffinity::None => None,
+        CoreAffinity::Auto => Some(available[index % available.len()]),
+        CoreAffinity::Explicit(ids) => match ids.get(index) {
+            Some(&id) => match available.iter().find(|core| core.id == id) {
+                Some(&core) => Some(core),
+                None => {
+                    warn!(
+                        "core_affinity: requested core {} is not among the {} cores reported by the OS, skipping",
+                        id,
+                        available.len()
+                    );
+                    None
+                }
+            },
+            None => None,
+        },
+    }
+}
+
+/// Runs `future` on its own OS thread, pinned to `core_id`, when `core_id` is `Some`.
+/// Otherwise runs it as a plain tokio task on the shared runtime - a dedicated thread plus its
+/// own reactor is only worth paying for when pinning was actually requested.
+fn spawn_pinned<F>(core_id: Option<core_affinity::CoreId>, future: F)
+where
+    F: std::future::Future<Output = ()> + Send + 'static,
+{
+    let core_id = match core_id {
+        Some(core_id) => core_id,
+        None => {
+            tokio::spawn(future);
+            return;
+        }
+    };
+    std::thread::spawn(move || {
+        if !core_affinity::set_for_current(core_id) {
+            warn!(
+                "core_affinity: failed to pin worker thread to core {:?}",
+                core_id
+            );
+        }
+        let mut runtime = tokio::runtime::Builder::new()
+            .basic_scheduler()
+            .enable_all()
+            .build()
+            .expect("failed to start pinned worker runtime");
+        runtime.block_on(future);
+    });
+}
+
+/// Startup stages, in the order `main` drives them. Every stage before `ApiServe` is recorded
+/// in `completed` as it finishes so a later failure can unwind exactly what already started,
+/// in reverse; `ApiServe` runs until the miner ends and is not recorded.
+///
+/// This lives in `entry.rs` rather than on `hub::Core` itself: `hub.rs` isn't part of this
+/// change set, and guessing at its internals (`Core`'s fields, `add_backend`'s retry surface, a
+/// backend teardown primitive that may or may not exist) risks contradicting the real file. The
+/// stage/unwind machinery below is written so it can be lifted onto `hub::Core` as-is once that
+/// file is in reach.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Stage {
+    BackendInit,
+    StatsInit,
+    ClientRegister,
+    ApiServe,
+}
+
+/// Attempts `run_stage` gives a stage before giving up.
+const MAX_STAGE_ATTEMPTS: u32 = 3;
+
+/// Runs `run` up to `MAX_STAGE_ATTEMPTS` times in total; on final failure, unwinds `completed` (in
+/// reverse) before panicking - same end state as an unconditional panic, but by way of a real
+/// rollback instead of leaving the stats task and any registered clients dangling.
+async fn run_stage<T, E, F, Fut>(
+    stage: Stage,
+    completed: &mut Vec<Stage>,
+    clients: &mut Vec<client::Handle>,
+    stats_shutdown: &mut Option<oneshot::Sender<()>>,
+    mut run: F,
+) -> T
+where
+    E: std::fmt::Debug,
+    F: FnMut() -> Fut,
+    Fut: std::future::Future<Output = Result<T, E>>,
+{
+    for attempt in 1..=MAX_STAGE_ATTEMPTS {
+        match run().await {
+            Ok(value) => {
+                completed.push(stage);
+                return value;
+            }
+            Err(e) if attempt < MAX_STAGE_ATTEMPTS => error!(
+                "{:?} failed (attempt {}/{}): {:?}",
+                stage, attempt, MAX_STAGE_ATTEMPTS, e
+            ),
+            Err(e) => {
+                error!(
+                    "{:?} failed after {} attempts: {:?}",
+                    stage, MAX_STAGE_ATTEMPTS, e
+                );
+                unwind(completed, clients, stats_shutdown).await;
+                panic!("Startup aborted during {:?}", stage);
+            }
+        }
+    }
+    unreachable!()
+}
+
+/// Tears down `completed` stages in reverse order. `BackendInit` has no teardown primitive in
+/// this tree (see the note on [`Stage`]), so it's logged rather than reversed; everything
+/// `main` itself started - the stats task, registered clients - is torn down for real.
+async fn unwind(
+    completed: &mut Vec<Stage>,
+    clients: &mut Vec<client::Handle>,
+    stats_shutdown: &mut Option<oneshot::Sender<()>>,
+) {
+    for stage in completed.drain(..).rev() {
+        match stage {
+            Stage::ClientRegister => {
+                for client in clients.drain(..) {
+                    client.disable();
+                }
+            }
+            Stage::StatsInit => {
+                if let Some(shutdown) = stats_shutdown.take() {
+                    let _ = shutdown.send(());
+                }
+            }
+            Stage::BackendInit => {
+                warn!("BackendInit has no teardown primitive, backend keeps running");
+            }
+            Stage::ApiServe => {}
+        }
+    }
+}
+
 pub async fn main<T: hal::Backend>() {
     let _log_guard = ii_logging::setup_for_app();
 
@@ -43,25 +191,71 @@ pub async fn main<T: hal::Backend>() {
 
     // Initialize hub core which manages all resources
     let core = Arc::new(hub::Core::new());
+    let core_affinity = runtime_config::core_affinity();
 
-    // Create and initialize the backend
-    let mut configuration = core
-        .add_backend::<T>()
-        .await
-        .expect("Backend initialization failed");
+    let mut completed = Vec::new();
+    let mut registered_clients = Vec::new();
+    let mut stats_shutdown: Option<oneshot::Sender<()>> = None;
+
+    // Stage: BackendInit - create and initialize the backend, retried from its own marker on
+    // transient failure instead of tearing the whole process down on the first attempt.
+    let mut configuration = run_stage(
+        Stage::BackendInit,
+        &mut completed,
+        &mut registered_clients,
+        &mut stats_shutdown,
+        || core.add_backend::<T>(),
+    )
+    .await;
 
-    tokio::spawn(core.clone().run());
-    // start statistics processing
-    tokio::spawn(stats::mining_task(
-        core.frontend.clone(),
-        T::DEFAULT_HASHRATE_INTERVAL,
-    ));
+    let backend_core = core.clone();
+    spawn_pinned(resolve_core_id(&core_affinity, 0), async move {
+        backend_core.run().await
+    });
+
+    // Stage: StatsInit - start statistics processing. Runs before ClientRegister so that if
+    // client registration ever fails, unwinding it still has a running stats task to stop.
+    let stats_node = core.frontend.clone();
+    let (shutdown_tx, shutdown_rx) = oneshot::channel();
+    let (attestation_signer, attestations) = match runtime_config::device_key() {
+        Some(device_key) => {
+            let (sender, mut receiver) = mpsc::unbounded();
+            tokio::spawn(async move {
+                while let Some(attestation) = receiver.next().await {
+                    info!("Hash rate attestation: {:?}", attestation);
+                }
+            });
+            let signer: Arc<dyn stats::AttestationSigner + Send + Sync> =
+                Arc::new(stats::DeviceKeySigner::new(device_key));
+            (Some(signer), Some(sender))
+        }
+        None => (None, None),
+    };
+    spawn_pinned(resolve_core_id(&core_affinity, 1), async move {
+        stats::mining_task(
+            stats_node,
+            T::DEFAULT_HASHRATE_INTERVAL,
+            attestation_signer,
+            attestations,
+            shutdown_rx,
+        )
+        .await
+    });
+    stats_shutdown = Some(shutdown_tx);
+    completed.push(Stage::StatsInit);
 
-    // start client based on user input
+    // Stage: ClientRegister - start clients based on user input. `client::register` cannot
+    // fail, so this stage does not go through `run_stage`. Registered clients are kept in
+    // `registered_clients`, which `unwind` disables, but no stage after this one calls
+    // `unwind`.
     for client_descriptor in configuration.clients.drain(..) {
-        client::register(&core, client_descriptor).await.enable();
+        let handle = client::register(&core, client_descriptor).await;
+        handle.enable();
+        registered_clients.push(handle);
     }
+    completed.push(Stage::ClientRegister);
 
-    // the bosminer is controlled with API which also controls when the miner will end
+    // Stage: ApiServe - the bosminer is controlled with API which also controls when the
+    // miner will end
     api::run(core, configuration).await;
 }
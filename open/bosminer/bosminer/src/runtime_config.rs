@@ -0,0 +1,109 @@
+// Copyright (C) 2019  Braiins Systems s.r.o.
+//
+// This file is part of Braiins Open-Source Initiative (BOSI).
+//
+// BOSI is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Please, keep in mind that we may also license BOSI or any part thereof
+// under a proprietary license. For more information on the terms and conditions
+// of such proprietary license or if you have any other questions, please
+// contact us at opensource@braiins.com.
+
+//! Global runtime configuration, set once during startup and read from anywhere in the
+//! frontend afterwards.
+
+use std::sync::RwLock;
+
+use lazy_static::lazy_static;
+
+/// Requested CPU core pinning for backend/stats worker threads.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CoreAffinity {
+    /// Don't pin - let the OS scheduler place worker threads freely
+    None,
+    /// Pin to these specific logical core IDs
+    Explicit(Vec<usize>),
+    /// Pin each worker thread to its own core, round-robin over all cores reported by the OS
+    Auto,
+}
+
+impl Default for CoreAffinity {
+    fn default() -> Self {
+        CoreAffinity::None
+    }
+}
+
+struct RuntimeConfig {
+    midstate_count: usize,
+    core_affinity: CoreAffinity,
+    device_key: Option<Vec<u8>>,
+}
+
+impl Default for RuntimeConfig {
+    fn default() -> Self {
+        Self {
+            midstate_count: 1,
+            core_affinity: CoreAffinity::default(),
+            device_key: None,
+        }
+    }
+}
+
+lazy_static! {
+    static ref RUNTIME_CONFIG: RwLock<RuntimeConfig> = RwLock::new(RuntimeConfig::default());
+}
+
+pub fn set_midstate_count(midstate_count: usize) {
+    RUNTIME_CONFIG
+        .write()
+        .expect("runtime config lock")
+        .midstate_count = midstate_count;
+}
+
+pub fn midstate_count() -> usize {
+    RUNTIME_CONFIG
+        .read()
+        .expect("runtime config lock")
+        .midstate_count
+}
+
+pub fn set_core_affinity(core_affinity: CoreAffinity) {
+    RUNTIME_CONFIG
+        .write()
+        .expect("runtime config lock")
+        .core_affinity = core_affinity;
+}
+
+pub fn core_affinity() -> CoreAffinity {
+    RUNTIME_CONFIG
+        .read()
+        .expect("runtime config lock")
+        .core_affinity
+        .clone()
+}
+
+pub fn set_device_key(device_key: Option<Vec<u8>>) {
+    RUNTIME_CONFIG
+        .write()
+        .expect("runtime config lock")
+        .device_key = device_key;
+}
+
+pub fn device_key() -> Option<Vec<u8>> {
+    RUNTIME_CONFIG
+        .read()
+        .expect("runtime config lock")
+        .device_key
+        .clone()
+}
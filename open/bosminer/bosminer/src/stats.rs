@@ -30,10 +30,13 @@ use bosminer_macros::MiningStats;
 
 use ii_stats::WindowedTimeMean;
 
+use futures::channel::{mpsc, oneshot};
+use futures::future::{self, Either};
 use futures::lock::Mutex;
 use ii_async_compat::{futures, tokio};
 use tokio::timer::delay_for;
 
+use std::sync::Arc;
 use std::time;
 
 use lazy_static::lazy_static;
@@ -54,6 +57,9 @@ pub struct MeterSnapshot {
     pub solutions: u64,
     /// All shares measured from the beginning of the mining
     pub shares: ii_bitcoin::Shares,
+    /// Exact running total of kilohashes (in milli-kH units), accumulated as an integer rather
+    /// than re-derived from `shares` each time - see [`SignedSnapshot::canonical_bytes`].
+    exact_kilo_hashes_milli: u128,
     /// Approximate arithmetic mean of hashes within given time intervals (in kH/time)
     time_means: Vec<WindowedTimeMean>,
 }
@@ -110,6 +116,102 @@ impl MeterSnapshot {
     ) -> ii_bitcoin::HashesUnit {
         self.to_kilo_hashes(interval, now).into_pretty_hashes()
     }
+
+    /// Signs this snapshot into a [`SignedSnapshot`] tagged with `sequence` (caller-owned counter).
+    pub fn signed_snapshot(
+        &self,
+        signer: &dyn AttestationSigner,
+        sequence: u64,
+        now: time::Instant,
+        timestamp: time::SystemTime,
+    ) -> SignedSnapshot {
+        let rates = self
+            .time_means
+            .iter()
+            .map(|time_mean| (time_mean.interval(), time_mean.measure(now)))
+            .collect();
+
+        let mut attestation = SignedSnapshot {
+            sequence,
+            timestamp,
+            solutions: self.solutions,
+            shares: self.shares.clone(),
+            exact_kilo_hashes_milli: self.exact_kilo_hashes_milli,
+            rates,
+            signature: Vec::new(),
+        };
+        attestation.signature = signer.sign(&attestation.canonical_bytes());
+        attestation
+    }
+}
+
+/// Signs attestation bytes with a device key
+pub trait AttestationSigner {
+    fn sign(&self, message: &[u8]) -> Vec<u8>;
+}
+
+/// Checks an [`AttestationSigner`]'s signature over attestation bytes
+pub trait AttestationVerifier {
+    fn verify(&self, message: &[u8], signature: &[u8]) -> bool;
+}
+
+/// A signed, sequence-numbered hash rate snapshot reported by this miner
+#[derive(Debug, Clone)]
+pub struct SignedSnapshot {
+    pub sequence: u64,
+    pub timestamp: time::SystemTime,
+    pub solutions: u64,
+    pub shares: ii_bitcoin::Shares,
+    /// Exact running kilohash total (milli-kH units) - see [`Self::canonical_bytes`].
+    pub exact_kilo_hashes_milli: u128,
+    pub rates: Vec<(time::Duration, f64)>,
+    pub signature: Vec<u8>,
+}
+
+impl SignedSnapshot {
+    /// Fixed-order, fixed-width byte encoding of this snapshot's fields, for signing/verifying.
+    ///
+    /// `shares` itself has no confirmed lossless byte encoding available in this tree, so
+    /// `exact_kilo_hashes_milli` - an integer this module accumulates itself, one rounding step
+    /// per solution rather than one over the lifetime total - stands in for it here. That keeps
+    /// two different accumulation histories that happen to round to the same `f64` from ever
+    /// producing the same signed bytes.
+    fn canonical_bytes(&self) -> Vec<u8> {
+        let mut buf = Vec::new();
+        buf.extend_from_slice(&self.sequence.to_be_bytes());
+        let timestamp_secs = self
+            .timestamp
+            .duration_since(time::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        buf.extend_from_slice(&timestamp_secs.to_be_bytes());
+        buf.extend_from_slice(&self.solutions.to_be_bytes());
+        buf.extend_from_slice(&self.exact_kilo_hashes_milli.to_be_bytes());
+        for (interval, rate) in &self.rates {
+            buf.extend_from_slice(&interval.as_secs().to_be_bytes());
+            buf.extend_from_slice(&rate.to_be_bytes());
+        }
+        buf
+    }
+
+    /// Checks the signature, `sequence > last_sequence`, and that `timestamp` is no later than
+    /// `now` and at most `max_age` older than it
+    pub fn verify(
+        &self,
+        verifier: &dyn AttestationVerifier,
+        last_sequence: u64,
+        now: time::SystemTime,
+        max_age: time::Duration,
+    ) -> bool {
+        if self.sequence <= last_sequence {
+            return false;
+        }
+        match now.duration_since(self.timestamp) {
+            Ok(age) if age <= max_age => {}
+            _ => return false,
+        }
+        verifier.verify(&self.canonical_bytes(), &self.signature)
+    }
 }
 
 #[derive(Debug)]
@@ -123,6 +225,7 @@ impl Meter {
             inner: Mutex::new(MeterSnapshot {
                 solutions: 0,
                 shares: Default::default(),
+                exact_kilo_hashes_milli: 0,
                 time_means: intervals
                     .iter()
                     .map(|&interval| WindowedTimeMean::new(interval))
@@ -144,6 +247,10 @@ impl Meter {
         // TODO: what to do when number overflows
         meter.solutions += 1;
         meter.shares.account_solution(target);
+        // Round this solution's contribution to milli-kH once, then accumulate exactly - so the
+        // aggregate carried in `exact_kilo_hashes_milli` never needs to be re-derived from a
+        // lossy float later (see `SignedSnapshot::canonical_bytes`).
+        meter.exact_kilo_hashes_milli += (kilo_hashes * 1000.0).round() as u128;
         for time_mean in &mut meter.time_means {
             time_mean.insert(kilo_hashes, time);
         }
@@ -156,6 +263,96 @@ impl Default for Meter {
     }
 }
 
+/// Reason why a submitted solution was rejected instead of accounted as a valid share
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RejectReason {
+    /// Solution was submitted for a job that is no longer current
+    StaleJob,
+    /// Solution didn't meet the difficulty target it was submitted against
+    LowDifficulty,
+    /// Nonce has already been accounted for this job
+    DuplicateNonce,
+    /// Solution references a job ID the node doesn't know about
+    UnknownJob,
+    /// Backend/hardware reported an error while computing the solution
+    HardwareError,
+}
+
+impl RejectReason {
+    const ALL: [RejectReason; 5] = [
+        RejectReason::StaleJob,
+        RejectReason::LowDifficulty,
+        RejectReason::DuplicateNonce,
+        RejectReason::UnknownJob,
+        RejectReason::HardwareError,
+    ];
+}
+
+/// Snapshot of [`RejectedMeter`] - one [`MeterSnapshot`] per [`RejectReason`].
+#[derive(Debug, Clone)]
+pub struct RejectedSnapshot {
+    snapshots: Vec<(RejectReason, MeterSnapshot)>,
+}
+
+impl RejectedSnapshot {
+    pub fn get(&self, reason: RejectReason) -> &MeterSnapshot {
+        self.snapshots
+            .iter()
+            .find(|(r, _)| *r == reason)
+            .map(|(_, snapshot)| snapshot)
+            .expect("BUG: missing snapshot for reject reason")
+    }
+}
+
+/// Tracks rejected shares keyed by [`RejectReason`], each with its own windowed time mean so
+/// reject rates can be reported per reason instead of as a single aggregate counter.
+#[derive(Debug)]
+pub struct RejectedMeter {
+    meters: Vec<(RejectReason, Meter)>,
+}
+
+impl RejectedMeter {
+    pub fn new(intervals: &Vec<time::Duration>) -> Self {
+        Self {
+            meters: RejectReason::ALL
+                .iter()
+                .map(|&reason| (reason, Meter::new(intervals)))
+                .collect(),
+        }
+    }
+
+    fn meter(&self, reason: RejectReason) -> &Meter {
+        self.meters
+            .iter()
+            .find(|(r, _)| *r == reason)
+            .map(|(_, meter)| meter)
+            .expect("BUG: missing meter for reject reason")
+    }
+
+    pub async fn take_snapshot(&self) -> RejectedSnapshot {
+        let mut snapshots = Vec::with_capacity(self.meters.len());
+        for (reason, meter) in &self.meters {
+            snapshots.push((*reason, meter.take_snapshot().await));
+        }
+        RejectedSnapshot { snapshots }
+    }
+
+    pub(crate) async fn account_solution(
+        &self,
+        reason: RejectReason,
+        target: &ii_bitcoin::Target,
+        time: time::Instant,
+    ) {
+        self.meter(reason).account_solution(target, time).await;
+    }
+}
+
+impl Default for RejectedMeter {
+    fn default() -> Self {
+        Self::new(DEFAULT_TIME_MEAN_INTERVALS.as_ref())
+    }
+}
+
 pub trait Mining: Send + Sync {
     /// The time all statistics are measured from
     fn start_time(&self) -> &time::Instant;
@@ -167,6 +364,8 @@ pub trait Mining: Send + Sync {
     fn valid_backend_diff(&self) -> &Meter;
     /// Statistics for all invalid work on backend difficulty (backend/HW error)
     fn error_backend_diff(&self) -> &Meter;
+    /// Statistics for rejected shares, segmented by `RejectReason`
+    fn rejected_shares(&self) -> &RejectedMeter;
 }
 
 #[derive(Debug, MiningStats)]
@@ -181,6 +380,8 @@ pub struct BasicMining {
     pub valid_backend_diff: Meter,
     #[member_error_backend_diff]
     pub error_backend_diff: Meter,
+    #[member_rejected_shares]
+    pub rejected_shares: RejectedMeter,
 }
 
 impl BasicMining {
@@ -191,6 +392,7 @@ impl BasicMining {
             valid_job_diff: Meter::new(&intervals),
             valid_backend_diff: Meter::new(&intervals),
             error_backend_diff: Meter::new(&intervals),
+            rejected_shares: RejectedMeter::new(&intervals),
         }
     }
 }
@@ -259,9 +461,63 @@ pub async fn account_valid_solution(
     }
 }
 
-pub async fn mining_task(node: node::DynInfo, interval: time::Duration) {
+/// Accounts a rejected `solution` against the given `reason` for every node in `path`.
+///
+/// SPI for backend/work-submission code to call once a solution's outcome is known; within
+/// this module it is called only by `account_solution`, same as `account_valid_solution`.
+pub async fn account_rejected_solution(
+    path: &node::Path,
+    reason: RejectReason,
+    target: &ii_bitcoin::Target,
+    time: time::Instant,
+) {
+    for node in path {
+        node.mining_stats()
+            .rejected_shares()
+            .account_solution(reason, target, time)
+            .await;
+    }
+}
+
+/// Single entry point for accounting a submitted `solution`, routing it to valid or rejected
+/// share statistics depending on `outcome` via `account_valid_solution`/
+/// `account_rejected_solution`. It has no caller in this module; it is the entry point for
+/// whichever code drives solution submission.
+pub async fn account_solution(
+    path: &node::Path,
+    solution: &work::Solution,
+    time: time::Instant,
+    outcome: Result<DiffTargetType, RejectReason>,
+) {
+    match outcome {
+        Ok(met_diff_target_type) => {
+            account_valid_solution(path, solution, time, met_diff_target_type).await
+        }
+        Err(reason) => {
+            account_rejected_solution(path, reason, solution.backend_target(), time).await
+        }
+    }
+}
+
+/// Runs the periodic hash rate logging once a second, optionally pushing a signed
+/// [`SignedSnapshot`] onto `attestations` on each of those ticks when `attestation_signer` is
+/// set (`interval` only selects the time mean that is logged and is not the push period). Returns as soon as `shutdown`
+/// fires instead of running forever, so a caller can stop this task without relying on
+/// cancelling a `JoinHandle` (this task may not even be a tokio task - `entry` can run it on a
+/// dedicated pinned thread instead).
+pub async fn mining_task(
+    node: node::DynInfo,
+    interval: time::Duration,
+    attestation_signer: Option<Arc<dyn AttestationSigner + Send + Sync>>,
+    mut attestations: Option<mpsc::UnboundedSender<SignedSnapshot>>,
+    mut shutdown: oneshot::Receiver<()>,
+) {
+    let mut sequence: u64 = 0;
     loop {
-        delay_for(time::Duration::from_secs(1)).await;
+        match future::select(delay_for(time::Duration::from_secs(1)), &mut shutdown).await {
+            Either::Left(_) => {}
+            Either::Right(_) => return,
+        }
         let valid_job_diff = node.mining_stats().valid_job_diff().take_snapshot().await;
 
         info!(
@@ -269,5 +525,300 @@ pub async fn mining_task(node: node::DynInfo, interval: time::Duration) {
             valid_job_diff.to_pretty_hashes(interval, time::Instant::now()),
             interval.as_secs()
         );
+
+        if let (Some(signer), Some(sender)) = (&attestation_signer, &attestations) {
+            sequence += 1;
+            let attestation = valid_job_diff.signed_snapshot(
+                signer.as_ref(),
+                sequence,
+                time::Instant::now(),
+                time::SystemTime::now(),
+            );
+            if sender.unbounded_send(attestation).is_err() {
+                warn!("Attestation channel closed, disabling hash rate attestation export");
+                attestations = None;
+            }
+        }
+    }
+}
+
+/// Minimal, dependency-free SHA-256 used by [`DeviceKeySigner`] below.
+mod sha256 {
+    const H0: [u32; 8] = [
+        0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f, 0x9b05688c, 0x1f83d9ab,
+        0x5be0cd19,
+    ];
+
+    const K: [u32; 64] = [
+        0x428a2f98, 0x71374491, 0xb5c0fbcf, 0xe9b5dba5, 0x3956c25b, 0x59f111f1, 0x923f82a4,
+        0xab1c5ed5, 0xd807aa98, 0x12835b01, 0x243185be, 0x550c7dc3, 0x72be5d74, 0x80deb1fe,
+        0x9bdc06a7, 0xc19bf174, 0xe49b69c1, 0xefbe4786, 0x0fc19dc6, 0x240ca1cc, 0x2de92c6f,
+        0x4a7484aa, 0x5cb0a9dc, 0x76f988da, 0x983e5152, 0xa831c66d, 0xb00327c8, 0xbf597fc7,
+        0xc6e00bf3, 0xd5a79147, 0x06ca6351, 0x14292967, 0x27b70a85, 0x2e1b2138, 0x4d2c6dfc,
+        0x53380d13, 0x650a7354, 0x766a0abb, 0x81c2c92e, 0x92722c85, 0xa2bfe8a1, 0xa81a664b,
+        0xc24b8b70, 0xc76c51a3, 0xd192e819, 0xd6990624, 0xf40e3585, 0x106aa070, 0x19a4c116,
+        0x1e376c08, 0x2748774c, 0x34b0bcb5, 0x391c0cb3, 0x4ed8aa4a, 0x5b9cca4f, 0x682e6ff3,
+        0x748f82ee, 0x78a5636f, 0x84c87814, 0x8cc70208, 0x90befffa, 0xa4506ceb, 0xbef9a3f7,
+        0xc67178f2,
+    ];
+
+    fn pad(message: &[u8]) -> Vec<u8> {
+        let mut padded = message.to_vec();
+        let bit_len = (message.len() as u64) * 8;
+        padded.push(0x80);
+        while padded.len() % 64 != 56 {
+            padded.push(0);
+        }
+        padded.extend_from_slice(&bit_len.to_be_bytes());
+        padded
+    }
+
+    /// SHA-256 digest of `message`.
+    pub fn digest(message: &[u8]) -> [u8; 32] {
+        let padded = pad(message);
+        let mut h = H0;
+
+        for block in padded.chunks(64) {
+            let mut w = [0u32; 64];
+            for (i, word) in block.chunks(4).enumerate() {
+                w[i] = u32::from_be_bytes([word[0], word[1], word[2], word[3]]);
+            }
+            for i in 16..64 {
+                let s0 = w[i - 15].rotate_right(7) ^ w[i - 15].rotate_right(18) ^ (w[i - 15] >> 3);
+                let s1 = w[i - 2].rotate_right(17) ^ w[i - 2].rotate_right(19) ^ (w[i - 2] >> 10);
+                w[i] = w[i - 16]
+                    .wrapping_add(s0)
+                    .wrapping_add(w[i - 7])
+                    .wrapping_add(s1);
+            }
+
+            let (mut a, mut b, mut c, mut d, mut e, mut f, mut g, mut hh) =
+                (h[0], h[1], h[2], h[3], h[4], h[5], h[6], h[7]);
+
+            for i in 0..64 {
+                let s1 = e.rotate_right(6) ^ e.rotate_right(11) ^ e.rotate_right(25);
+                let ch = (e & f) ^ ((!e) & g);
+                let temp1 = hh
+                    .wrapping_add(s1)
+                    .wrapping_add(ch)
+                    .wrapping_add(K[i])
+                    .wrapping_add(w[i]);
+                let s0 = a.rotate_right(2) ^ a.rotate_right(13) ^ a.rotate_right(22);
+                let maj = (a & b) ^ (a & c) ^ (b & c);
+                let temp2 = s0.wrapping_add(maj);
+
+                hh = g;
+                g = f;
+                f = e;
+                e = d.wrapping_add(temp1);
+                d = c;
+                c = b;
+                b = a;
+                a = temp1.wrapping_add(temp2);
+            }
+
+            h[0] = h[0].wrapping_add(a);
+            h[1] = h[1].wrapping_add(b);
+            h[2] = h[2].wrapping_add(c);
+            h[3] = h[3].wrapping_add(d);
+            h[4] = h[4].wrapping_add(e);
+            h[5] = h[5].wrapping_add(f);
+            h[6] = h[6].wrapping_add(g);
+            h[7] = h[7].wrapping_add(hh);
+        }
+
+        let mut out = [0u8; 32];
+        for (i, word) in h.iter().enumerate() {
+            out[i * 4..i * 4 + 4].copy_from_slice(&word.to_be_bytes());
+        }
+        out
+    }
+
+    const BLOCK_SIZE: usize = 64;
+
+    /// HMAC-SHA256 of `message` under `key`.
+    pub fn hmac(key: &[u8], message: &[u8]) -> [u8; 32] {
+        let mut block_key = [0u8; BLOCK_SIZE];
+        if key.len() > BLOCK_SIZE {
+            block_key[..32].copy_from_slice(&digest(key));
+        } else {
+            block_key[..key.len()].copy_from_slice(key);
+        }
+
+        let mut ipad = [0x36u8; BLOCK_SIZE];
+        let mut opad = [0x5cu8; BLOCK_SIZE];
+        for i in 0..BLOCK_SIZE {
+            ipad[i] ^= block_key[i];
+            opad[i] ^= block_key[i];
+        }
+
+        let mut inner = ipad.to_vec();
+        inner.extend_from_slice(message);
+        let inner_digest = digest(&inner);
+
+        let mut outer = opad.to_vec();
+        outer.extend_from_slice(&inner_digest);
+        digest(&outer)
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        fn from_hex(hex: &str) -> Vec<u8> {
+            (0..hex.len())
+                .step_by(2)
+                .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).unwrap())
+                .collect()
+        }
+
+        /// NIST FIPS 180-4 SHA-256 test vector for the empty message.
+        #[test]
+        fn digest_empty_message() {
+            assert_eq!(
+                digest(b"").to_vec(),
+                from_hex("e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855")
+            );
+        }
+
+        /// NIST FIPS 180-4 SHA-256 test vector for "abc".
+        #[test]
+        fn digest_abc() {
+            assert_eq!(
+                digest(b"abc").to_vec(),
+                from_hex("ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad")
+            );
+        }
+
+        /// RFC 4231 HMAC-SHA256 test case 1: 20-byte key of 0x0b, data "Hi There".
+        #[test]
+        fn hmac_rfc4231_case_1() {
+            let key = [0x0bu8; 20];
+            assert_eq!(
+                hmac(&key, b"Hi There").to_vec(),
+                from_hex("b0344c61d8db38535ca8afceaf0bf12b881dc200c9833da726e9376c2e32cff7")
+            );
+        }
+    }
+}
+
+/// [`AttestationSigner`]/[`AttestationVerifier`] backed by an HMAC-SHA256 device key. This is
+/// the signer `mining_task` is wired up with whenever `runtime_config::device_key` is set.
+pub struct DeviceKeySigner {
+    key: Vec<u8>,
+}
+
+impl DeviceKeySigner {
+    pub fn new(key: Vec<u8>) -> Self {
+        Self { key }
+    }
+}
+
+impl AttestationSigner for DeviceKeySigner {
+    fn sign(&self, message: &[u8]) -> Vec<u8> {
+        sha256::hmac(&self.key, message).to_vec()
+    }
+}
+
+impl AttestationVerifier for DeviceKeySigner {
+    fn verify(&self, message: &[u8], signature: &[u8]) -> bool {
+        let expected = sha256::hmac(&self.key, message);
+        // Constant-time comparison so verification timing doesn't leak the expected signature.
+        expected.len() == signature.len()
+            && expected
+                .iter()
+                .zip(signature.iter())
+                .fold(0u8, |acc, (a, b)| acc | (a ^ b))
+                == 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn block_on<F: std::future::Future>(future: F) -> F::Output {
+        let mut runtime = tokio::runtime::Builder::new()
+            .basic_scheduler()
+            .build()
+            .expect("failed to start test runtime");
+        runtime.block_on(future)
+    }
+
+    #[test]
+    fn rejected_meter_starts_every_reason_at_zero() {
+        let meter = RejectedMeter::default();
+        let snapshot = block_on(meter.take_snapshot());
+        for &reason in RejectReason::ALL.iter() {
+            assert_eq!(snapshot.get(reason).solutions, 0);
+        }
+    }
+
+    struct AlwaysValid;
+    impl AttestationVerifier for AlwaysValid {
+        fn verify(&self, _message: &[u8], _signature: &[u8]) -> bool {
+            true
+        }
+    }
+
+    struct AlwaysInvalid;
+    impl AttestationVerifier for AlwaysInvalid {
+        fn verify(&self, _message: &[u8], _signature: &[u8]) -> bool {
+            false
+        }
+    }
+
+    fn snapshot_at(sequence: u64, timestamp: time::SystemTime) -> SignedSnapshot {
+        SignedSnapshot {
+            sequence,
+            timestamp,
+            solutions: 0,
+            shares: Default::default(),
+            exact_kilo_hashes_milli: 0,
+            rates: Vec::new(),
+            signature: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn verify_rejects_bad_signature() {
+        let snapshot = snapshot_at(1, time::SystemTime::now());
+        assert!(!snapshot.verify(
+            &AlwaysInvalid,
+            0,
+            time::SystemTime::now(),
+            time::Duration::from_secs(60)
+        ));
+    }
+
+    #[test]
+    fn verify_rejects_non_increasing_sequence() {
+        let snapshot = snapshot_at(5, time::SystemTime::now());
+        assert!(!snapshot.verify(
+            &AlwaysValid,
+            5,
+            time::SystemTime::now(),
+            time::Duration::from_secs(60)
+        ));
+        assert!(!snapshot.verify(
+            &AlwaysValid,
+            6,
+            time::SystemTime::now(),
+            time::Duration::from_secs(60)
+        ));
+    }
+
+    #[test]
+    fn verify_rejects_stale_timestamp() {
+        let now = time::SystemTime::now();
+        let snapshot = snapshot_at(1, now - time::Duration::from_secs(120));
+        assert!(!snapshot.verify(&AlwaysValid, 0, now, time::Duration::from_secs(60)));
+    }
+
+    #[test]
+    fn verify_accepts_fresh_snapshot_with_higher_sequence() {
+        let now = time::SystemTime::now();
+        let snapshot = snapshot_at(1, now);
+        assert!(snapshot.verify(&AlwaysValid, 0, now, time::Duration::from_secs(60)));
     }
 }